@@ -7,6 +7,9 @@ pub struct HttpRequest {
     protocol: String,
     headers: HashMap<String, String>,
     body: String,
+    query: HashMap<String, String>,
+    form: HashMap<String, String>,
+    route_params: HashMap<String, String>,
 }
 impl HttpRequest {
     pub fn from(req_str: String) -> Result<Self> {
@@ -38,6 +41,14 @@ impl HttpRequest {
             }
         }
 
+        let query = parse_urlencoded(&params);
+        let form = match headers.get("Content-Type") {
+            Some(ct) if ct.starts_with("application/x-www-form-urlencoded") => {
+                parse_urlencoded(&body)
+            }
+            _ => HashMap::new(),
+        };
+
         Ok(HttpRequest {
             params,
             path,
@@ -45,6 +56,9 @@ impl HttpRequest {
             protocol,
             headers,
             body,
+            query,
+            form,
+            route_params: HashMap::new(),
         })
     }
 
@@ -71,4 +85,72 @@ impl HttpRequest {
     pub fn get_params(&self) -> &str {
         &self.params
     }
+
+    pub fn get_query(&self, key: &str) -> Option<&str> {
+        self.query.get(key).map(|v| v.as_str())
+    }
+
+    pub fn query_map(&self) -> &HashMap<String, String> {
+        &self.query
+    }
+
+    pub fn form_map(&self) -> &HashMap<String, String> {
+        &self.form
+    }
+
+    pub fn get_param(&self, name: &str) -> Option<&str> {
+        self.route_params.get(name).map(|v| v.as_str())
+    }
+
+    /// Attach the path parameters captured by a dynamic route (`/users/:id`).
+    pub fn set_route_params(&mut self, params: HashMap<String, String>) {
+        self.route_params = params;
+    }
+}
+
+/// Parse an `application/x-www-form-urlencoded` string (`a=1&b=hi+there`) into
+/// a map, applying percent-decoding and `+`-to-space conversion to each side.
+/// Repeated keys keep the last value; missing values decode to an empty string.
+fn parse_urlencoded(input: &str) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    for pair in input.split('&') {
+        if pair.is_empty() {
+            continue;
+        }
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        map.insert(decode_component(key), decode_component(value));
+    }
+    map
+}
+
+/// Decode a single URL-encoded component: `+` becomes a space and `%XX`
+/// becomes the corresponding byte; malformed escapes are passed through.
+fn decode_component(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => {
+                out.push(b' ');
+                i += 1;
+            }
+            b'%' if i + 2 < bytes.len() => {
+                let hi = (bytes[i + 1] as char).to_digit(16);
+                let lo = (bytes[i + 2] as char).to_digit(16);
+                if let (Some(hi), Some(lo)) = (hi, lo) {
+                    out.push((hi * 16 + lo) as u8);
+                    i += 3;
+                } else {
+                    out.push(bytes[i]);
+                    i += 1;
+                }
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
 }
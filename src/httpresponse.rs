@@ -3,7 +3,8 @@ use std::collections::HashMap;
 pub struct HttpResponse {
     status: String,
     headers: HashMap<String, String>,
-    body: String,
+    body: Vec<u8>,
+    finalized: bool,
 }
 
 impl HttpResponse {
@@ -11,12 +12,31 @@ impl HttpResponse {
         HttpResponse {
             status: "HTTP/1.1 200 OK".to_owned(),
             headers: HashMap::new(),
-            body: String::new(),
+            body: Vec::new(),
+            finalized: false,
         }
     }
 
+    /// Mark this response as fully prepared, stopping any further middlewares
+    /// and route dispatch so it is written to the socket exactly once.
+    pub fn finalize(&mut self) {
+        self.finalized = true;
+    }
+
+    pub fn is_finalized(&self) -> bool {
+        self.finalized
+    }
+
     pub fn set_body(&mut self, body: &str) {
-        self.body = body.to_string();
+        self.body = body.as_bytes().to_vec();
+    }
+
+    pub fn set_body_bytes(&mut self, body: &[u8]) {
+        self.body = body.to_vec();
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
     }
 
     pub fn set_header(&mut self, key: &str, value: &str) {
@@ -35,4 +55,10 @@ impl HttpResponse {
         response.push_str("\r\n");
         response
     }
+
+    pub fn get_string(&self) -> String {
+        let mut response = self.get_header();
+        response.push_str(&String::from_utf8_lossy(&self.body));
+        response
+    }
 }
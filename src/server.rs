@@ -1,11 +1,13 @@
 use std::{
-    collections::HashMap,
+    collections::{hash_map::DefaultHasher, HashMap},
     fs::{self},
+    hash::{Hash, Hasher},
     io::{Read, Result, Write},
     net::{TcpListener, TcpStream},
     path::Path,
     sync::Arc,
     thread::spawn,
+    time::UNIX_EPOCH,
 };
 
 use crate::{Handler, HttpRequest, HttpResponse, Middleware};
@@ -13,8 +15,10 @@ use crate::{Handler, HttpRequest, HttpResponse, Middleware};
 pub struct HttpServer {
     listener: TcpListener,
     routers: Arc<HashMap<(String, String), Arc<Handler>>>,
+    dynamic_routers: Arc<Vec<(String, String, Arc<Handler>)>>,
     middlewares: Arc<Vec<Arc<Middleware>>>,
     static_route: Arc<Vec<(String, String)>>,
+    mime_table: Arc<HashMap<String, String>>,
 }
 
 impl HttpServer {
@@ -23,20 +27,37 @@ impl HttpServer {
         Ok(HttpServer {
             listener,
             routers: Arc::new(HashMap::new()),
+            dynamic_routers: Arc::new(Vec::new()),
             middlewares: Arc::new(Vec::new()),
             static_route: Arc::new(Vec::new()),
+            mime_table: Arc::new(HashMap::new()),
         })
     }
 
+    /// Load an extension-to-MIME table from a `mime.types`-style file (for
+    /// example `/etc/mime.types`), consulted before the built-in matches.
+    pub fn with_mime_table(&mut self, path: &str) {
+        self.mime_table = Arc::new(load_mime_table(path));
+    }
+
     pub fn run(self) -> Result<()> {
         for stream in self.listener.incoming() {
             match stream {
                 Ok(stream) => {
                     let routers = Arc::clone(&self.routers);
+                    let dynamic_routers = Arc::clone(&self.dynamic_routers);
                     let middlewares = Arc::clone(&self.middlewares);
                     let static_route = Arc::clone(&self.static_route);
+                    let mime_table = Arc::clone(&self.mime_table);
                     spawn(move || {
-                        if let Err(e) = handle_stream(stream, routers, middlewares, static_route) {
+                        if let Err(e) = handle_stream(
+                            stream,
+                            routers,
+                            dynamic_routers,
+                            middlewares,
+                            static_route,
+                            mime_table,
+                        ) {
                             eprintln!("Error handling stream: {}", e);
                         }
                     });
@@ -51,9 +72,17 @@ impl HttpServer {
     where
         F: Fn(&mut HttpRequest, &mut HttpResponse) + Send + Sync + 'static,
     {
-        Arc::get_mut(&mut self.routers)
-            .unwrap()
-            .insert((method.to_string(), path.to_string()), Arc::new(handler));
+        if path.split('/').any(|seg| seg.starts_with(':')) {
+            Arc::get_mut(&mut self.dynamic_routers).unwrap().push((
+                method.to_string(),
+                path.to_string(),
+                Arc::new(handler),
+            ));
+        } else {
+            Arc::get_mut(&mut self.routers)
+                .unwrap()
+                .insert((method.to_string(), path.to_string()), Arc::new(handler));
+        }
     }
 
     pub fn add_middleware<F>(&mut self, middleware: F)
@@ -87,84 +116,452 @@ impl HttpServer {
 fn handle_stream(
     mut stream: TcpStream,
     route: Arc<HashMap<(String, String), Arc<Handler>>>,
+    dynamic_route: Arc<Vec<(String, String, Arc<Handler>)>>,
     middlewares: Arc<Vec<Arc<Middleware>>>,
     static_route: Arc<Vec<(String, String)>>,
+    mime_table: Arc<HashMap<String, String>>,
 ) -> Result<()> {
-    let req_str = read_stream_to_httpstr(&stream)?;
-    let mut req = HttpRequest::from(req_str)?;
-    let mut res = HttpResponse::new();
-
-    if static_route.len() >= 1 {
-        for (path, dir) in static_route.iter() {
-            if path.len() < req.get_path().len() {
-                if req.get_path().get(..path.len()).unwrap() == path
-                    && req.get_path().chars().nth(path.len()).unwrap() == '/'
-                {
-                    let file_str = format!(
-                        "{}/{}",
-                        dir,
-                        req.get_path()
-                            .get((path.len() + 1)..)
-                            .unwrap_or("not_found")
-                    );
-                    if Path::new(&file_str).exists() {
-                        let contents = fs::read(&file_str).unwrap();
-                        res.set_header("Content-Type", get_mime_type(&file_str));
-                        res.set_body(&String::from_utf8_lossy(&contents));
-                        stream.write_all(res.get_string().as_bytes())?;
-                        stream.flush()?
-                    } else {
-                        res.set_body("file not found");
-                        stream.write_all(res.get_string().as_bytes())?;
-                        stream.flush()?
+    let mut pending: Vec<u8> = Vec::new();
+    loop {
+        let req_str = match read_stream_to_httpstr(&mut stream, &mut pending)? {
+            Some(req_str) => req_str,
+            None => break,
+        };
+        let mut req = HttpRequest::from(req_str)?;
+        let mut res = HttpResponse::new();
+
+        let keep_alive = connection_keep_alive(&req);
+        res.set_header("Connection", if keep_alive { "keep-alive" } else { "close" });
+
+        if static_route.len() >= 1 {
+            for (path, dir) in static_route.iter() {
+                if path.len() < req.get_path().len() {
+                    if req.get_path().get(..path.len()).unwrap() == path
+                        && req.get_path().chars().nth(path.len()).unwrap() == '/'
+                    {
+                        let file_str = format!(
+                            "{}/{}",
+                            dir,
+                            req.get_path()
+                                .get((path.len() + 1)..)
+                                .unwrap_or("not_found")
+                        );
+                        match fs::metadata(&file_str) {
+                            Ok(meta) if meta.is_dir() => {
+                                let index =
+                                    format!("{}/index.html", file_str.trim_end_matches('/'));
+                                if Path::new(&index).exists() {
+                                    serve_file(&req, &mut res, &index, &mime_table)?;
+                                } else {
+                                    res.set_header("Content-Type", "text/html");
+                                    res.set_body(&render_dir_index(&file_str, req.get_path()));
+                                }
+                            }
+                            Ok(_) => serve_file(&req, &mut res, &file_str, &mime_table)?,
+                            Err(_) => {
+                                res.set_body("file not found");
+                            }
+                        }
+                        // A static hit is fully prepared; skip middlewares and routing.
+                        res.finalize();
                     }
                 }
             }
         }
-    }
 
-    if middlewares.len() >= 1 {
-        for middleware in middlewares.iter() {
-            middleware(&mut req, &mut res);
+        if !res.is_finalized() {
+            for middleware in middlewares.iter() {
+                middleware(&mut req, &mut res);
+                if res.is_finalized() {
+                    break;
+                }
+            }
         }
-    }
 
-    let key = (req.get_method().to_string(), req.get_path().to_string());
-    if let Some(handler) = route.get(&key) {
-        handler(&mut req, &mut res);
-        stream.write_all(res.get_string().as_bytes())?;
-        stream.flush()?;
-    } else {
-        res.set_body("404 Not Found");
-        res.set_header("Content-Type", "text/plain");
-        stream.write_all(res.get_string().as_bytes())?;
+        if !res.is_finalized() {
+            let key = (req.get_method().to_string(), req.get_path().to_string());
+            if let Some(handler) = route.get(&key) {
+                handler(&mut req, &mut res);
+            } else if let Some((handler, params)) =
+                match_dynamic(&dynamic_route, req.get_method(), req.get_path())
+            {
+                req.set_route_params(params);
+                handler(&mut req, &mut res);
+            } else {
+                res.set_body("404 Not Found");
+                res.set_header("Content-Type", "text/plain");
+            }
+        }
+
+        stream.write_all(res.get_header().as_bytes())?;
+        stream.write_all(res.body())?;
         stream.flush()?;
+
+        if !keep_alive {
+            break;
+        }
     }
 
     Ok(())
 }
-fn read_stream_to_httpstr(mut stream: &TcpStream) -> Result<String> {
-    let mut buf = vec![0; 512];
-    let mut result = String::new();
-    loop {
-        let read = stream.read(&mut buf)?;
+
+/// Decide whether to keep the connection open after this request: HTTP/1.1
+/// defaults to keep-alive, HTTP/1.0 to close, either overridable by the header.
+fn connection_keep_alive(req: &HttpRequest) -> bool {
+    match req.get_header("Connection") {
+        Some(c) if c.eq_ignore_ascii_case("close") => false,
+        Some(c) if c.eq_ignore_ascii_case("keep-alive") => true,
+        _ => req.get_protocol() == "HTTP/1.1",
+    }
+}
+
+/// Read a single HTTP request from the stream: first up to the `\r\n\r\n`
+/// header terminator, then exactly `Content-Length` body bytes. Returns
+/// `Ok(None)` when the peer closed the connection without sending anything.
+/// Bytes read past the end of the request are kept in `pending` and used as
+/// the start of the next request on the same connection.
+fn read_stream_to_httpstr(
+    stream: &mut TcpStream,
+    pending: &mut Vec<u8>,
+) -> Result<Option<String>> {
+    let mut buf: Vec<u8> = std::mem::take(pending);
+    let mut chunk = [0u8; 512];
+
+    let header_end = loop {
+        if let Some(pos) = find_subsequence(&buf, b"\r\n\r\n") {
+            break pos + 4;
+        }
+        let read = stream.read(&mut chunk)?;
         if read == 0 {
-            break;
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            break buf.len();
         }
-        result.push_str(&String::from_utf8_lossy(&buf[..read]));
-        if read < buf.len() {
+        buf.extend_from_slice(&chunk[..read]);
+    };
+
+    let content_length = String::from_utf8_lossy(&buf[..header_end])
+        .lines()
+        .find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            if key.trim().eq_ignore_ascii_case("content-length") {
+                value.trim().parse::<usize>().ok()
+            } else {
+                None
+            }
+        })
+        .unwrap_or(0);
+
+    while buf.len() - header_end < content_length {
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
             break;
         }
+        buf.extend_from_slice(&chunk[..read]);
+    }
+
+    let request_end = (header_end + content_length).min(buf.len());
+    *pending = buf.split_off(request_end);
+
+    Ok(Some(String::from_utf8_lossy(&buf).into_owned()))
+}
+
+/// Find the first occurrence of `needle` within `haystack`.
+fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Find the most-specific dynamic route matching `method` and `path`. When
+/// several patterns match, the one capturing the fewest `:name` segments wins.
+fn match_dynamic(
+    routes: &[(String, String, Arc<Handler>)],
+    method: &str,
+    path: &str,
+) -> Option<(Arc<Handler>, HashMap<String, String>)> {
+    let mut best: Option<(usize, Arc<Handler>, HashMap<String, String>)> = None;
+    for (m, pattern, handler) in routes {
+        if m != method {
+            continue;
+        }
+        if let Some(params) = match_pattern(pattern, path) {
+            let score = params.len();
+            if best.as_ref().map(|(s, _, _)| score < *s).unwrap_or(true) {
+                best = Some((score, Arc::clone(handler), params));
+            }
+        }
     }
-    // println!("{}", &result);
-    Ok(result)
+    best.map(|(_, handler, params)| (handler, params))
+}
+
+/// Match a `/users/:id` pattern against a concrete path segment-by-segment,
+/// returning the captured parameters on success.
+fn match_pattern(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+    let pat: Vec<&str> = pattern.trim_matches('/').split('/').collect();
+    let seg: Vec<&str> = path.trim_matches('/').split('/').collect();
+    if pat.len() != seg.len() {
+        return None;
+    }
+    let mut params = HashMap::new();
+    for (p, s) in pat.iter().zip(seg.iter()) {
+        if let Some(name) = p.strip_prefix(':') {
+            params.insert(name.to_string(), s.to_string());
+        } else if p != s {
+            return None;
+        }
+    }
+    Some(params)
+}
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Format a Unix timestamp as an RFC 7231 IMF-fixdate, e.g.
+/// `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn http_date(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    let (hour, min, sec) = (rem / 3600, (rem % 3600) / 60, rem % 60);
+    // 1970-01-01 was a Thursday (index 4).
+    let weekday = (((days % 7) + 4) % 7) as usize;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[weekday], day, MONTHS[(month - 1) as usize], year, hour, min, sec
+    )
 }
 
-fn get_mime_type(file_path: &str) -> &str {
-    match Path::new(file_path)
+/// Parse an RFC 7231 IMF-fixdate back into a Unix timestamp. Returns `None` for
+/// anything we do not recognise.
+fn parse_http_date(s: &str) -> Option<u64> {
+    let parts: Vec<&str> = s.split_whitespace().collect();
+    if parts.len() < 5 {
+        return None;
+    }
+    let day: u32 = parts[1].parse().ok()?;
+    let month = MONTHS.iter().position(|&m| m == parts[2])? as u32 + 1;
+    let year: i64 = parts[3].parse().ok()?;
+    let time: Vec<&str> = parts[4].split(':').collect();
+    if time.len() != 3 {
+        return None;
+    }
+    let h: u64 = time[0].parse().ok()?;
+    let m: u64 = time[1].parse().ok()?;
+    let s: u64 = time[2].parse().ok()?;
+    let days = days_from_civil(year, month, day);
+    Some((days as u64) * 86400 + h * 3600 + m * 60 + s)
+}
+
+/// Convert a day count since the Unix epoch to a `(year, month, day)` civil
+/// date (Howard Hinnant's algorithm).
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+    let z = z + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let y = yoe + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (if m <= 2 { y + 1 } else { y }, m, d)
+}
+
+/// Inverse of [`civil_from_days`]: days since the Unix epoch for a civil date.
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = if m > 2 { m - 3 } else { m + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + d as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Populate `res` with a single file, honouring conditional-GET and `Range`
+/// headers. The caller is responsible for writing the response to the socket.
+fn serve_file(
+    req: &HttpRequest,
+    res: &mut HttpResponse,
+    file_str: &str,
+    mime_table: &HashMap<String, String>,
+) -> Result<()> {
+    let meta = fs::metadata(file_str)?;
+    let size = meta.len();
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs());
+
+    // Weak validator derived from the file's size and mtime.
+    let etag = mtime.map(|m| {
+        let mut hasher = DefaultHasher::new();
+        size.hash(&mut hasher);
+        m.hash(&mut hasher);
+        format!("W/\"{:x}\"", hasher.finish())
+    });
+
+    // `If-None-Match` takes precedence over `If-Modified-Since` when both exist.
+    let fresh = match &etag {
+        Some(etag) => {
+            if let Some(inm) = req.get_header("If-None-Match") {
+                inm.trim() == etag
+            } else if let (Some(ims), Some(mtime)) =
+                (req.get_header("If-Modified-Since"), mtime)
+            {
+                parse_http_date(ims).map(|since| mtime <= since).unwrap_or(false)
+            } else {
+                false
+            }
+        }
+        None => false,
+    };
+
+    if let Some(etag) = &etag {
+        res.set_header("ETag", etag);
+    }
+    if let Some(mtime) = mtime {
+        res.set_header("Last-Modified", &http_date(mtime));
+    }
+
+    if fresh {
+        res.set_status("HTTP/1.1 304 Not Modified".to_owned());
+        res.set_body_bytes(&[]);
+        return Ok(());
+    }
+
+    res.set_header("Cache-Control", "max-age=0, must-revalidate");
+    let contents = fs::read(file_str)?;
+    let total = contents.len() as u64;
+    res.set_header("Content-Type", get_mime_type(file_str, mime_table));
+    res.set_header("Accept-Ranges", "bytes");
+    match req.get_header("Range").and_then(|r| parse_range(r, total)) {
+        Some(RangeResult::Satisfiable(start, end)) => {
+            res.set_status("HTTP/1.1 206 Partial Content".to_owned());
+            res.set_header("Content-Range", &format!("bytes {}-{}/{}", start, end, total));
+            res.set_body_bytes(&contents[start as usize..=end as usize]);
+        }
+        Some(RangeResult::Unsatisfiable) => {
+            res.set_status("HTTP/1.1 416 Range Not Satisfiable".to_owned());
+            res.set_header("Content-Range", &format!("bytes */{}", total));
+            res.set_body_bytes(&[]);
+        }
+        None => {
+            res.set_body_bytes(&contents);
+        }
+    }
+    Ok(())
+}
+
+/// Render an HTML directory listing for `dir`, with hrefs rooted at the request
+/// path `req_path` so links resolve correctly under the static prefix.
+fn render_dir_index(dir: &str, req_path: &str) -> String {
+    let base = if req_path.ends_with('/') {
+        req_path.to_owned()
+    } else {
+        format!("{}/", req_path)
+    };
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n<body>\n<h1>Index of {0}</h1>\n<ul>\n",
+        base
+    );
+    html.push_str("<li><a href=\"../\">../</a></li>\n");
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let suffix = if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                "/"
+            } else {
+                ""
+            };
+            html.push_str(&format!(
+                "<li><a href=\"{0}{1}{2}\">{1}{2}</a></li>\n",
+                base, name, suffix
+            ));
+        }
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    html
+}
+
+/// Outcome of resolving a `Range` request header against a file of known length.
+enum RangeResult {
+    /// A clamped, satisfiable byte interval `[start, end]` (inclusive).
+    Satisfiable(u64, u64),
+    /// The range could not be satisfied; the caller should reply `416`.
+    Unsatisfiable,
+}
+
+/// Parse a `Range: bytes=...` header value against a resource of length `len`.
+///
+/// Returns `None` when the header is absent in spirit (not a `bytes=` range we
+/// understand), in which case the whole file should be served with `200 OK`.
+fn parse_range(header: &str, len: u64) -> Option<RangeResult> {
+    let spec = header.strip_prefix("bytes=")?;
+    let (start_s, end_s) = spec.split_once('-')?;
+
+    if len == 0 {
+        return Some(RangeResult::Unsatisfiable);
+    }
+
+    let (start, end) = if start_s.trim().is_empty() {
+        // bytes=-N : the final N bytes of the file.
+        let n: u64 = end_s.trim().parse().ok()?;
+        if n == 0 {
+            return Some(RangeResult::Unsatisfiable);
+        }
+        let n = n.min(len);
+        (len - n, len - 1)
+    } else {
+        let start: u64 = start_s.trim().parse().ok()?;
+        let end = if end_s.trim().is_empty() {
+            len - 1
+        } else {
+            end_s.trim().parse::<u64>().ok()?.min(len - 1)
+        };
+        (start, end)
+    };
+
+    if start > end || start >= len {
+        return Some(RangeResult::Unsatisfiable);
+    }
+    Some(RangeResult::Satisfiable(start, end))
+}
+
+/// Parse a `mime.types`-style file into an extension-to-MIME-type map. Each
+/// non-comment line is a MIME type followed by whitespace-separated extensions.
+fn load_mime_table(path: &str) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    if let Ok(contents) = fs::read_to_string(path) {
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            if let Some(mime) = fields.next() {
+                for ext in fields {
+                    map.insert(ext.to_owned(), mime.to_owned());
+                }
+            }
+        }
+    }
+    map
+}
+
+fn get_mime_type<'a>(file_path: &'a str, mime_table: &'a HashMap<String, String>) -> &'a str {
+    let ext = Path::new(file_path)
         .extension()
-        .and_then(|ext| ext.to_str())
-    {
+        .and_then(|ext| ext.to_str());
+    if let Some(mime) = ext.and_then(|ext| mime_table.get(ext)) {
+        return mime;
+    }
+    match ext {
         Some("html") => "text/html",
         Some("css") => "text/css",
         Some("js") => "application/javascript",